@@ -1,14 +1,26 @@
-use std::collections::{HashMap, HashSet};
-use std::fs::OpenOptions;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use chrono::Utc;
-use clap::Parser;
-use pnet::datalink::{self, Channel::Ethernet, Config};
-use pnet::packet::{ethernet::EthernetPacket, ip::IpNextHeaderProtocols, ipv4::Ipv4Packet, tcp::TcpPacket, Packet};
+use clap::{Parser, ValueEnum};
+use crossbeam_channel::{bounded, TrySendError};
+use pnet::datalink::{self, Channel::Ethernet, Config, FanoutOption, FanoutType, NetworkInterface};
+use pnet::packet::{
+    ethernet::EthernetPacket,
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    tcp::TcpFlags,
+    tcp::TcpPacket,
+    Packet,
+};
+use serde::Serialize;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -17,10 +29,14 @@ struct Args {
     #[arg(short, long)]
     iface: String,
 
-    /// Number of unique ports to trigger alert
+    /// Number of unique ports to trigger alert for a generic connect/SYN/ACK scan
     #[arg(short, long, default_value_t = 20)]
     threshold: usize,
 
+    /// Number of unique ports to trigger alert for NULL/FIN/XMAS stealth scans
+    #[arg(short, long, default_value_t = 5)]
+    stealth_threshold: usize,
+
     /// Time window in seconds
     #[arg(short, long, default_value_t = 60)]
     window: u64,
@@ -28,140 +44,690 @@ struct Args {
     /// File to log alerts
     #[arg(short, long, default_value = "alerts.log")]
     log_file: String,
+
+    /// Number of parallel capture threads, load-balanced by the kernel via Linux packet fanout
+    #[arg(long, default_value_t = 1)]
+    workers: usize,
+
+    /// Alert output format
+    #[arg(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+
+    /// Capacity of the bounded queues between the capture, detection, and logging stages (per worker)
+    #[arg(long, default_value_t = 4096)]
+    queue_capacity: usize,
+
+    /// Large window (seconds) also tracked per source, to catch low-and-slow scanners that stay
+    /// under the short-window threshold
+    #[arg(long, default_value_t = 3600)]
+    slow_scan_window: u64,
+}
+
+/// Alert output format: human-readable text, or one JSON object per line for SIEM ingestion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// The TCP flag patterns we fingerprint as known scan techniques.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ScanClass {
+    /// SYN set, ACK clear: half-open "stealth" connect scan.
+    Syn,
+    /// No flags set at all.
+    Null,
+    /// FIN only.
+    Fin,
+    /// FIN + PSH + URG.
+    Xmas,
+    /// ACK only: firewall/stateful-filter mapping, not a connect attempt.
+    Ack,
+}
+
+impl ScanClass {
+    fn label(self) -> &'static str {
+        match self {
+            ScanClass::Syn => "SYN scan",
+            ScanClass::Null => "NULL scan",
+            ScanClass::Fin => "FIN scan",
+            ScanClass::Xmas => "XMAS scan",
+            ScanClass::Ack => "ACK scan",
+        }
+    }
+
+    /// Alert threshold is much lower for flag patterns that never occur in legitimate traffic.
+    fn is_stealth(self) -> bool {
+        matches!(self, ScanClass::Null | ScanClass::Fin | ScanClass::Xmas)
+    }
+
+    /// Classify a raw TCP flags byte, or `None` if it doesn't match a known scan pattern.
+    fn classify(flags: u8) -> Option<ScanClass> {
+        match flags {
+            0 => Some(ScanClass::Null),
+            TcpFlags::FIN => Some(ScanClass::Fin),
+            f if f == TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG => Some(ScanClass::Xmas),
+            TcpFlags::ACK => Some(ScanClass::Ack),
+            f if f & TcpFlags::SYN != 0 && f & TcpFlags::ACK == 0 => Some(ScanClass::Syn),
+            _ => None,
+        }
+    }
+}
+
+/// Unique ports seen from one source within a trailing window; stale entries are evicted as
+/// they age out rather than all at once.
+#[derive(Default)]
+struct PortWindow {
+    seen: HashMap<u16, Instant>,
+}
+
+impl PortWindow {
+    fn record(&mut self, port: u16, now: Instant, window: Duration) -> usize {
+        self.seen
+            .retain(|_, seen_at| now.duration_since(*seen_at) <= window);
+        self.seen.insert(port, now);
+        self.seen.len()
+    }
+
+    fn ports_vec(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.seen.keys().copied().collect();
+        ports.sort_unstable();
+        ports
+    }
+}
+
+/// Caps how often one source/scan-class pair can fire an alert while its scan is ongoing.
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, refill_per_sec: f64, now: Instant) -> Self {
+        TokenBucket {
+            tokens: capacity,
+            capacity,
+            refill_per_sec,
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
 }
 
 struct IpActivity {
-    ports: HashSet<u16>,
-    first_seen: Instant,
+    scans: HashMap<ScanClass, PortWindow>,
+    slow_scans: HashMap<ScanClass, PortWindow>,
+    limiters: HashMap<ScanClass, TokenBucket>,
+    last_seen: Instant,
+}
+
+impl IpActivity {
+    fn new(now: Instant) -> Self {
+        IpActivity {
+            scans: HashMap::new(),
+            slow_scans: HashMap::new(),
+            limiters: HashMap::new(),
+            last_seen: now,
+        }
+    }
 }
 
-fn log_alert(path: &str, message: &str) {
-    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+fn open_log_file(path: &str) -> File {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap_or_else(|e| {
+            eprintln!("❌ Failed to open log file '{}': {}", path, e);
+            process::exit(1);
+        })
+}
+
+fn log_alert(log: &Mutex<File>, message: &str) {
+    if let Ok(mut file) = log.lock() {
         let _ = writeln!(file, "{}", message);
     }
 }
 
-fn main() {
-    let args = Args::parse();
+/// NDJSON representation of an alert, shared by the console and file writers.
+#[derive(Serialize)]
+struct AlertRecord {
+    timestamp: String,
+    src_ip: String,
+    scan_type: String,
+    unique_ports: usize,
+    window_secs: u64,
+    port_list: Vec<u16>,
+}
 
-    println!(
-        "[{}] Starting rust-ids on interface '{}' with threshold={} ports, window={}s",
-        Utc::now().format("%Y-%m-%d %H:%M:%S"),
-        args.iface,
-        args.threshold,
-        args.window
-    );
+/// Render an alert in the configured `--format`; `window_secs` is whichever window (short or
+/// `--slow-scan-window`) actually crossed the threshold.
+fn format_alert(
+    args: &Args,
+    source_ip: IpAddr,
+    scan_class: ScanClass,
+    ports: &[u16],
+    window_secs: u64,
+    is_slow: bool,
+) -> String {
+    let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let scan_type = if is_slow {
+        format!("slow-and-low {}", scan_class.label())
+    } else {
+        scan_class.label().to_string()
+    };
 
-    let interfaces = datalink::interfaces();
-    let interface = interfaces
-        .into_iter()
-        .find(|iface| iface.name == args.iface)
-        .unwrap_or_else(|| {
-            eprintln!("❌ Interface '{}' not found", args.iface);
-            process::exit(1);
-        });
+    match args.format {
+        OutputFormat::Text => format!(
+            "[{}] ⚠️ Potential {} from {}: {} ports in {}s",
+            timestamp,
+            scan_type,
+            source_ip,
+            ports.len(),
+            window_secs
+        ),
+        OutputFormat::Json => {
+            let record = AlertRecord {
+                timestamp,
+                src_ip: source_ip.to_string(),
+                scan_type,
+                unique_ports: ports.len(),
+                window_secs,
+                port_list: ports.to_vec(),
+            };
+            serde_json::to_string(&record).unwrap_or_default()
+        }
+    }
+}
 
-    let mut config = Config::default();
-    config.read_timeout = Some(Duration::from_millis(1000));
+/// Walk the IPv6 extension header chain (Hop-by-Hop, Routing, Fragment) to find the TCP header.
+fn find_ipv6_tcp_payload(
+    mut next_header: IpNextHeaderProtocol,
+    mut payload: &[u8],
+) -> Option<&[u8]> {
+    loop {
+        match next_header {
+            IpNextHeaderProtocols::Tcp => return Some(payload),
+            IpNextHeaderProtocols::Hopopt | IpNextHeaderProtocols::Ipv6Route => {
+                if payload.len() < 2 {
+                    return None;
+                }
+                let ext_len = (payload[1] as usize + 1) * 8;
+                if payload.len() < ext_len {
+                    return None;
+                }
+                next_header = IpNextHeaderProtocol::new(payload[0]);
+                payload = &payload[ext_len..];
+            }
+            IpNextHeaderProtocols::Ipv6Frag => {
+                // The fragment header has a fixed 8-byte length.
+                if payload.len() < 8 {
+                    return None;
+                }
+                next_header = IpNextHeaderProtocol::new(payload[0]);
+                payload = &payload[8..];
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Parse one captured frame and update `ip_map`, returning a rendered alert if a scan threshold
+/// was crossed and the source's per-scan-class token bucket still has a token.
+fn handle_packet(
+    packet_data: &[u8],
+    ip_map: &mut HashMap<IpAddr, IpActivity>,
+    window: Duration,
+    slow_scan_window: Duration,
+    args: &Args,
+) -> Option<String> {
+    let ethernet = EthernetPacket::new(packet_data)?;
+
+    let (source_ip, tcp_payload): (IpAddr, &[u8]) = match ethernet.get_ethertype() {
+        pnet::packet::ethernet::EtherTypes::Ipv4 => {
+            let ipv4_payload = ethernet.payload();
+            if ipv4_payload.len() < Ipv4Packet::minimum_packet_size() {
+                return None;
+            }
+            let ipv4 = Ipv4Packet::new(ipv4_payload)?;
+            if ipv4.get_next_level_protocol() != IpNextHeaderProtocols::Tcp {
+                return None;
+            }
+
+            let ip_header_len = ipv4.get_header_length() as usize * 4;
+            if ipv4_payload.len() < ip_header_len {
+                eprintln!(
+                    "⚠️ IPv4 payload too short: {} bytes, expected at least {} bytes",
+                    ipv4_payload.len(),
+                    ip_header_len
+                );
+                return None;
+            }
+
+            (
+                IpAddr::V4(ipv4.get_source()),
+                &ipv4_payload[ip_header_len..],
+            )
+        }
+        pnet::packet::ethernet::EtherTypes::Ipv6 => {
+            let ipv6_payload = ethernet.payload();
+            if ipv6_payload.len() < Ipv6Packet::minimum_packet_size() {
+                return None;
+            }
+            let ipv6 = Ipv6Packet::new(ipv6_payload)?;
+            let source_ip = IpAddr::V6(ipv6.get_source());
+            let next_header = ipv6.get_next_header();
+            let ext_payload = &ipv6_payload[Ipv6Packet::minimum_packet_size()..];
+            let tcp_payload = find_ipv6_tcp_payload(next_header, ext_payload)?;
+
+            (source_ip, tcp_payload)
+        }
+        _ => return None,
+    };
+
+    if tcp_payload.len() < TcpPacket::minimum_packet_size() {
+        eprintln!(
+            "⚠️ TCP payload too short: {} bytes, expected at least {} bytes",
+            tcp_payload.len(),
+            TcpPacket::minimum_packet_size()
+        );
+        return None;
+    }
+
+    let Some(tcp) = TcpPacket::new(tcp_payload) else {
+        eprintln!("⚠️ Failed to parse TCP packet.");
+        return None;
+    };
 
+    let scan_class = ScanClass::classify(tcp.get_flags())?;
+
+    let dst_port = tcp.get_destination();
+    let now = Instant::now();
+
+    let activity = ip_map
+        .entry(source_ip)
+        .or_insert_with(|| IpActivity::new(now));
+    activity.last_seen = now;
+
+    let short_count = activity
+        .scans
+        .entry(scan_class)
+        .or_default()
+        .record(dst_port, now, window);
+    let slow_count =
+        activity
+            .slow_scans
+            .entry(scan_class)
+            .or_default()
+            .record(dst_port, now, slow_scan_window);
+
+    let scan_threshold = if scan_class.is_stealth() {
+        args.stealth_threshold
+    } else {
+        args.threshold
+    };
+
+    let trigger = if short_count >= scan_threshold {
+        Some((
+            activity.scans[&scan_class].ports_vec(),
+            window.as_secs(),
+            false,
+        ))
+    } else if slow_count >= scan_threshold {
+        Some((
+            activity.slow_scans[&scan_class].ports_vec(),
+            slow_scan_window.as_secs(),
+            true,
+        ))
+    } else {
+        None
+    };
+
+    let (ports, window_secs, is_slow) = trigger?;
+
+    let limiter = activity
+        .limiters
+        .entry(scan_class)
+        .or_insert_with(|| TokenBucket::new(1.0, 1.0 / window.as_secs_f64().max(1.0), now));
+    if !limiter.try_take(now) {
+        return None;
+    }
+
+    Some(format_alert(
+        args,
+        source_ip,
+        scan_class,
+        &ports,
+        window_secs,
+        is_slow,
+    ))
+}
+
+fn maybe_heartbeat(
+    worker_id: usize,
+    last_heartbeat: &mut Instant,
+    frame_queue_len: usize,
+    dropped_frames: usize,
+    alert_queue_len: usize,
+    dropped_alerts: usize,
+) {
+    if last_heartbeat.elapsed() >= Duration::from_secs(30) {
+        println!(
+            "[{}] ✅ IDS worker {} still running... frame_queue={} dropped_frames={} alert_queue={} dropped_alerts={}",
+            Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            worker_id,
+            frame_queue_len,
+            dropped_frames,
+            alert_queue_len,
+            dropped_alerts
+        );
+        *last_heartbeat = Instant::now();
+    }
+}
+
+/// Owns `ip_map`; drains raw frames from `frame_rx` and forwards rendered alerts to `alert_tx`
+/// until the capture thread closes the channel. Alerts are dropped (not blocked on) once
+/// `alert_tx` is full, so a slow disk in `run_logging` can't stall this thread and, in turn,
+/// stall `frame_rx` draining.
+fn run_detection(
+    args: &Args,
+    frame_rx: crossbeam_channel::Receiver<Vec<u8>>,
+    alert_tx: crossbeam_channel::Sender<String>,
+    dropped_alerts: &std::sync::atomic::AtomicUsize,
+) {
+    let mut ip_map: HashMap<IpAddr, IpActivity> = HashMap::new();
+    let window = Duration::from_secs(args.window);
+    let slow_scan_window = Duration::from_secs(args.slow_scan_window);
+    let mut last_prune = Instant::now();
+
+    for frame in frame_rx {
+        if let Some(message) = handle_packet(&frame, &mut ip_map, window, slow_scan_window, args) {
+            println!("{}", message);
+            if let Err(TrySendError::Full(_)) = alert_tx.try_send(message) {
+                dropped_alerts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+
+        // Drop sources idle past the largest tracked window to keep ip_map bounded.
+        if last_prune.elapsed() >= Duration::from_secs(30) {
+            let now = Instant::now();
+            ip_map.retain(|_, activity| now.duration_since(activity.last_seen) <= slow_scan_window);
+            last_prune = now;
+        }
+    }
+}
+
+/// The only thread of its worker's pipeline that writes to the shared log file (each worker has
+/// one, serialized by the mutex); drains `alert_rx` until detection closes it.
+fn run_logging(log: &Mutex<File>, alert_rx: crossbeam_channel::Receiver<String>) {
+    for message in alert_rx {
+        log_alert(log, &message);
+    }
+}
+
+/// One capture/detection/logging pipeline, owning its own `ip_map` shard. Capture only copies
+/// raw frames into a bounded queue, so a flood or a slow disk drops at the queue, not the NIC.
+fn run_capture(
+    worker_id: usize,
+    interface: NetworkInterface,
+    config: Config,
+    args: Arc<Args>,
+    log: Arc<Mutex<File>>,
+) {
     let (_, mut rx) = match datalink::channel(&interface, config) {
         Ok(Ethernet(_tx, rx)) => ((), rx),
         Ok(_) => {
-            eprintln!("❌ Unsupported channel type");
+            eprintln!("❌ [worker {}] Unsupported channel type", worker_id);
             process::exit(1);
         }
         Err(e) => {
-            eprintln!("❌ Failed to create datalink channel: {}", e);
+            eprintln!(
+                "❌ [worker {}] Failed to create datalink channel: {}",
+                worker_id, e
+            );
             process::exit(1);
         }
     };
 
-    let mut ip_map: HashMap<Ipv4Addr, IpActivity> = HashMap::new();
-    let window_duration = Duration::from_secs(args.window);
+    let (frame_tx, frame_rx) = bounded::<Vec<u8>>(args.queue_capacity);
+    let (alert_tx, alert_rx) = bounded::<String>(args.queue_capacity);
+
+    let logging_args = Arc::clone(&log);
+    let _logging_handle = thread::spawn(move || run_logging(&logging_args, alert_rx));
+
+    let dropped_alerts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let detection_alert_tx = alert_tx.clone();
+    let detection_dropped_alerts = Arc::clone(&dropped_alerts);
+    let detection_args = Arc::clone(&args);
+    let _detection_handle = thread::spawn(move || {
+        run_detection(
+            &detection_args,
+            frame_rx,
+            detection_alert_tx,
+            &detection_dropped_alerts,
+        )
+    });
+
     let mut last_heartbeat = Instant::now();
+    let mut dropped_frames: usize = 0;
 
     loop {
         match rx.next() {
             Ok(packet_data) => {
-                if let Some(ethernet) = EthernetPacket::new(packet_data) {
-                    if ethernet.get_ethertype() == pnet::packet::ethernet::EtherTypes::Ipv4 {
-                        let ipv4_payload = ethernet.payload();
-                        if ipv4_payload.len() >= Ipv4Packet::minimum_packet_size() {
-                            if let Some(ipv4) = Ipv4Packet::new(ipv4_payload) {
-                                if ipv4.get_next_level_protocol() == IpNextHeaderProtocols::Tcp {
-                                    let ip_header_len = ipv4.get_header_length() as usize * 4;
-                                    if ipv4_payload.len() < ip_header_len {
-                                        eprintln!(
-                                            "⚠️ IPv4 payload too short: {} bytes, expected at least {} bytes",
-                                            ipv4_payload.len(),
-                                            ip_header_len
-                                        );
-                                        continue;
-                                    }
-
-                                    let tcp_payload = &ipv4_payload[ip_header_len..];
-                                    if tcp_payload.len() < TcpPacket::minimum_packet_size() {
-                                        eprintln!(
-                                            "⚠️ TCP payload too short: {} bytes, expected at least {} bytes",
-                                            tcp_payload.len(),
-                                            TcpPacket::minimum_packet_size()
-                                        );
-                                        continue;
-                                    }
-
-                                    match TcpPacket::new(tcp_payload) {
-                                        Some(tcp) => {
-                                            let source_ip = ipv4.get_source();
-                                            let dst_port = tcp.get_destination();
-                                            let now = Instant::now();
-
-                                            ip_map.retain(|_, activity| {
-                                                now.duration_since(activity.first_seen) <= window_duration
-                                            });
-
-                                            let activity = ip_map.entry(source_ip).or_insert_with(|| IpActivity {
-                                                ports: HashSet::new(),
-                                                first_seen: now,
-                                            });
-
-                                            activity.ports.insert(dst_port);
-
-                                            if activity.ports.len() >= args.threshold {
-                                                let alert_msg = format!(
-                                                    "[{}] ⚠️ Potential port scan from {}: {} ports in {}s",
-                                                    Utc::now().format("%Y-%m-%d %H:%M:%S"),
-                                                    source_ip,
-                                                    activity.ports.len(),
-                                                    args.window
-                                                );
-                                                println!("{}", alert_msg);
-                                                log_alert(&args.log_file, &alert_msg);
-                                                ip_map.remove(&source_ip);
-                                            }
-                                        }
-                                        None => {
-                                            eprintln!("⚠️ Failed to parse TCP packet.");
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-
-                // Heartbeat every 30 seconds
-                if last_heartbeat.elapsed() >= Duration::from_secs(30) {
-                    println!("[{}] ✅ IDS still running...", Utc::now().format("%Y-%m-%d %H:%M:%S"));
-                    last_heartbeat = Instant::now();
+                if let Err(TrySendError::Full(_)) = frame_tx.try_send(packet_data.to_vec()) {
+                    dropped_frames += 1;
                 }
+                maybe_heartbeat(
+                    worker_id,
+                    &mut last_heartbeat,
+                    frame_tx.len(),
+                    dropped_frames,
+                    alert_tx.len(),
+                    dropped_alerts.load(std::sync::atomic::Ordering::Relaxed),
+                );
             }
             Err(_) => {
                 // Timeout occurred, check for heartbeat
-                if last_heartbeat.elapsed() >= Duration::from_secs(30) {
-                    println!("[{}] ✅ IDS still running...", Utc::now().format("%Y-%m-%d %H:%M:%S"));
-                    last_heartbeat = Instant::now();
-                }
+                maybe_heartbeat(
+                    worker_id,
+                    &mut last_heartbeat,
+                    frame_tx.len(),
+                    dropped_frames,
+                    alert_tx.len(),
+                    dropped_alerts.load(std::sync::atomic::Ordering::Relaxed),
+                );
             }
         }
     }
 }
+
+fn main() {
+    let args = Args::parse();
+
+    if args.slow_scan_window <= args.window {
+        eprintln!(
+            "❌ --slow-scan-window ({}) must be greater than --window ({})",
+            args.slow_scan_window, args.window
+        );
+        process::exit(1);
+    }
+
+    let args = Arc::new(args);
+
+    println!(
+        "[{}] Starting rust-ids on interface '{}' with threshold={} ports, window={}s, workers={}",
+        Utc::now().format("%Y-%m-%d %H:%M:%S"),
+        args.iface,
+        args.threshold,
+        args.window,
+        args.workers
+    );
+
+    let interfaces = datalink::interfaces();
+    let interface = interfaces
+        .into_iter()
+        .find(|iface| iface.name == args.iface)
+        .unwrap_or_else(|| {
+            eprintln!("❌ Interface '{}' not found", args.iface);
+            process::exit(1);
+        });
+
+    let log = Arc::new(Mutex::new(open_log_file(&args.log_file)));
+
+    if args.workers <= 1 {
+        let mut config = Config::default();
+        config.read_timeout = Some(Duration::from_millis(1000));
+        run_capture(0, interface, config, args, log);
+        return;
+    }
+
+    // Bind every worker to the same fanout group so the kernel load-balances flows across them by hash.
+    let fanout_group_id = (process::id() & 0xffff) as u16;
+    let handles: Vec<_> = (0..args.workers)
+        .map(|worker_id| {
+            let interface = interface.clone();
+            let args = Arc::clone(&args);
+            let log = Arc::clone(&log);
+            thread::spawn(move || {
+                let config = Config {
+                    read_timeout: Some(Duration::from_millis(1000)),
+                    linux_fanout: Some(FanoutOption {
+                        group_id: fanout_group_id,
+                        fanout_type: FanoutType::HASH,
+                        defrag: false,
+                        rollover: false,
+                    }),
+                    ..Config::default()
+                };
+                run_capture(worker_id, interface, config, args, log);
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn test_args() -> Args {
+        Args {
+            iface: "eth0".to_string(),
+            threshold: 20,
+            stealth_threshold: 5,
+            window: 60,
+            log_file: "alerts.log".to_string(),
+            workers: 1,
+            format: OutputFormat::Text,
+            queue_capacity: 4096,
+            slow_scan_window: 3600,
+        }
+    }
+
+    #[test]
+    fn classify_known_flag_patterns() {
+        assert_eq!(ScanClass::classify(0), Some(ScanClass::Null));
+        assert_eq!(ScanClass::classify(TcpFlags::FIN), Some(ScanClass::Fin));
+        assert_eq!(
+            ScanClass::classify(TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG),
+            Some(ScanClass::Xmas)
+        );
+        assert_eq!(ScanClass::classify(TcpFlags::ACK), Some(ScanClass::Ack));
+        assert_eq!(ScanClass::classify(TcpFlags::SYN), Some(ScanClass::Syn));
+    }
+
+    #[test]
+    fn classify_rejects_normal_handshake_flags() {
+        assert_eq!(ScanClass::classify(TcpFlags::SYN | TcpFlags::ACK), None);
+    }
+
+    #[test]
+    fn port_window_evicts_stale_entries() {
+        let mut window = PortWindow::default();
+        let t0 = Instant::now();
+        assert_eq!(window.record(80, t0, Duration::from_secs(60)), 1);
+        assert_eq!(window.record(443, t0, Duration::from_secs(60)), 2);
+
+        let t1 = t0 + Duration::from_secs(120);
+        assert_eq!(window.record(22, t1, Duration::from_secs(60)), 1);
+        assert_eq!(window.ports_vec(), vec![22]);
+    }
+
+    #[test]
+    fn token_bucket_limits_bursts_and_refills() {
+        let t0 = Instant::now();
+        let mut bucket = TokenBucket::new(1.0, 1.0, t0);
+
+        assert!(bucket.try_take(t0));
+        assert!(!bucket.try_take(t0));
+
+        let t1 = t0 + Duration::from_secs(1);
+        assert!(bucket.try_take(t1));
+    }
+
+    #[test]
+    fn format_alert_text_includes_scan_type_and_port_count() {
+        let args = test_args();
+        let message = format_alert(
+            &args,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            ScanClass::Syn,
+            &[22, 80, 443],
+            60,
+            false,
+        );
+        assert!(message.contains("SYN scan"));
+        assert!(message.contains("3 ports"));
+        assert!(!message.contains("slow-and-low"));
+    }
+
+    #[test]
+    fn format_alert_json_marks_slow_scans() {
+        let mut args = test_args();
+        args.format = OutputFormat::Json;
+        let message = format_alert(
+            &args,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            ScanClass::Fin,
+            &[22, 80],
+            3600,
+            true,
+        );
+        let record: serde_json::Value = serde_json::from_str(&message).unwrap();
+        assert_eq!(record["scan_type"], "slow-and-low FIN scan");
+        assert_eq!(record["unique_ports"], 2);
+    }
+
+    #[test]
+    fn find_ipv6_tcp_payload_skips_hop_by_hop_header() {
+        // Hop-by-Hop header: next header = TCP, hdr ext len = 0 (8-byte header).
+        let mut payload = vec![IpNextHeaderProtocols::Tcp.0, 0, 0, 0, 0, 0, 0, 0];
+        payload.extend_from_slice(b"tcp-bytes");
+
+        let tcp_payload = find_ipv6_tcp_payload(IpNextHeaderProtocols::Hopopt, &payload).unwrap();
+        assert_eq!(tcp_payload, b"tcp-bytes");
+    }
+
+    #[test]
+    fn find_ipv6_tcp_payload_returns_none_on_truncated_header() {
+        assert!(find_ipv6_tcp_payload(IpNextHeaderProtocols::Hopopt, &[0]).is_none());
+    }
+}